@@ -1,286 +1,213 @@
-use crate::register::{Flag, Mode, Register, R};
-use crate::{bit, reg_1st, reg_2nd, sign_extend};
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
-use std::convert::{TryFrom, TryInto};
-
-pub type CycleResult = Result<(), String>;
-
-#[derive(Debug, FromPrimitive)]
-#[repr(u16)]
-pub enum OpCode {
-    BR,   // 0000
-    ADD,  // 0001
-    LD,   // 0010
-    ST,   // 0011
-    JSR,  // 0100
-    AND,  // 0101
-    LDR,  // 0110
-    STR,  // 0111
-    RTI,  // 1000
-    NOT,  // 1001
-    LDI,  // 1010
-    STI,  // 1011
-    JMP,  // 1100 // JMP R7 == RET
-    RES,  // 1101 // reserved
-    LEA,  // 1110
-    TRAP, // 1111
-}
-
-impl TryFrom<u16> for OpCode {
-    type Error = String;
-
-    fn try_from(val: u16) -> Result<Self, Self::Error> {
-        OpCode::from_u16(val).ok_or(format!("unknown opcode `{}`", val))
-    }
+use crate::bus::{Addressable, Bus, Irq};
+use crate::exec::{self, CYCLES_INDIRECT_MEMORY_ACCESS};
+use crate::register::{Register, R};
+use std::collections::HashSet;
+
+/// Number of machine cycles an instruction consumes, or an error.
+pub use crate::exec::CycleResult;
+
+/// Instruction and cycle counts accumulated by [`CPU::run`]. These are
+/// bookkeeping, not machine state, so they are not part of the
+/// `save_state`/`load_state` snapshot (see `snapshot`).
+pub struct RunStats {
+    pub instructions: u64,
+    pub cycles: u64,
 }
 
 pub struct CPU {
     register: Register,
-    memory: [u16; u16::MAX as usize + 1],
+    bus: Bus,
+    running: bool,
+    instructions_executed: u64,
+    cycles_elapsed: u64,
+    pub(crate) breakpoints: HashSet<u16>,
+    // See `Debuggable::run` in `debugger`: the PC of the breakpoint last
+    // reported, so the next `run` can step over it instead of reporting it
+    // again with zero forward progress.
+    pub(crate) last_breakpoint_hit: Option<u16>,
 }
 
 impl CPU {
     pub fn new() -> Self {
         CPU {
             register: Register::new(),
-            memory: [0; u16::MAX as usize + 1],
-        }
-    }
-
-    pub fn tick(&mut self) -> CycleResult {
-        let boot_addr = self.register.read_incr(R::PC);
-        let instr: u16 = self.mem_read(boot_addr);
-        let opcode: OpCode = (instr >> 12).try_into()?;
-
-        match opcode {
-            OpCode::BR => self.mnemonic_br(instr)?,
-            OpCode::ADD => self.mnemonic_add(instr)?,
-            OpCode::LD => self.mnemonic_ld(instr)?,
-            OpCode::ST => self.mnemonic_st(instr)?,
-            OpCode::JSR => self.mnemonic_jsr(instr)?,
-            OpCode::AND => self.mnemonic_and(instr)?,
-            OpCode::LDR => self.mnemonic_ldr(instr)?,
-            OpCode::STR => self.mnemonic_str(instr)?,
-            OpCode::RTI => self.mnemonic_rti(instr)?,
-            OpCode::NOT => self.mnemonic_not(instr)?,
-            OpCode::LDI => self.mnemonic_ldi(instr)?,
-            OpCode::STI => self.mnemonic_sti(instr)?,
-            OpCode::JMP => self.mnemonic_jmp(instr)?,
-            OpCode::RES => self.mnemonic_res(instr)?,
-            OpCode::LEA => self.mnemonic_lea(instr)?,
-            OpCode::TRAP => self.mnemonic_trap(instr)?,
+            bus: Bus::new(),
+            running: true,
+            instructions_executed: 0,
+            cycles_elapsed: 0,
+            breakpoints: HashSet::new(),
+            last_breakpoint_hit: None,
         }
-
-        Ok(())
     }
 
-    pub fn reg_store(&mut self, r: R, val: u16) {
-        self.register.write(r, val);
+    pub fn is_running(&self) -> bool {
+        self.running
     }
 
-    pub fn reg_load(&self, r: R) -> u16 {
-        self.register.read(r)
+    pub fn abort(&mut self) {
+        self.running = false
     }
 
-    pub fn mem_read(&self, addr: u16) -> u16 {
-        self.memory[addr as usize]
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
     }
 
-    pub fn mem_write(&mut self, addr: u16, val: u16) {
-        self.memory[addr as usize] = val;
+    pub fn cycles_elapsed(&self) -> u64 {
+        self.cycles_elapsed
     }
 
-    fn mnemonic_br(&mut self, args: u16) -> CycleResult {
-        let offset = sign_extend(args & 0x9, 9);
-
-        let nzp = (args >> 9) & 0x7;
-        if nzp == 0 {
-            self.register
-                .write(R::PC, self.register.read(R::PC).wrapping_add(offset));
-            return Ok(());
-        }
-
-        let n: bool = bit(args, 11) == 1;
-        let z: bool = bit(args, 10) == 1;
-        let p: bool = bit(args, 9) == 1;
+    pub fn tick(&mut self) -> CycleResult {
+        let current_pl = self.register.get_level()? as u16;
+        let irq_cycles = match self.bus.take_irq(current_pl) {
+            Some(irq) => self.service_irq(irq)?,
+            None => 0,
+        };
 
-        let flag = self.register.get_flag()?;
+        let cycles = exec::step(&mut self.register, &mut self.bus, &mut self.running)?;
 
-        if (n && flag == Flag::Negative)
-            || (z && flag == Flag::Zero)
-            || (p && flag == Flag::Positive)
-        {
-            self.register
-                .write(R::PC, self.register.read(R::PC).wrapping_add(offset));
-        }
+        let total_cycles = irq_cycles + cycles;
+        self.instructions_executed += 1;
+        self.cycles_elapsed += total_cycles as u64;
 
-        Ok(())
+        Ok(total_cycles)
     }
 
-    fn mnemonic_imm5_or_sr2<F>(&mut self, args: u16, func: F) -> CycleResult
-    where
-        F: Fn(u16, u16) -> u16,
-    {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        let imm_flag: u16 = bit(args, 5);
-
-        if imm_flag == 1 {
-            self.register.write(
-                r0,
-                func(self.register.read(r1), sign_extend(args & 0x1f, 5)),
-            );
-        } else {
-            let r2: R = (args & 0x7).try_into()?;
-            self.register
-                .write(r0, func(self.register.read(r1), self.register.read(r2)));
-        }
-        self.register.update_flag(r0);
-        Ok(())
+    // See `interrupt::service_irq`.
+    fn service_irq(&mut self, irq: Irq) -> CycleResult {
+        crate::interrupt::service_irq(&mut self.register, &mut self.bus, irq)?;
+        Ok(CYCLES_INDIRECT_MEMORY_ACCESS)
     }
 
-    fn mnemonic_add(&mut self, args: u16) -> CycleResult {
-        self.mnemonic_imm5_or_sr2(args, |r1, r2| r1.wrapping_add(r2))
-    }
+    /// Runs until HALT, an error, or `max_cycles` is exhausted, whichever
+    /// comes first. `max_cycles` is compared against `cycles_elapsed`, the
+    /// total since the CPU was created, and the returned counts are those
+    /// running totals, not only the part spent in this call.
+    pub fn run(&mut self, max_cycles: u64) -> Result<RunStats, String> {
+        while self.is_running() && self.cycles_elapsed < max_cycles {
+            self.tick()?;
+        }
 
-    fn mnemonic_and(&mut self, args: u16) -> CycleResult {
-        self.mnemonic_imm5_or_sr2(args, |r1, r2| r1 & r2)
+        Ok(RunStats {
+            instructions: self.instructions_executed,
+            cycles: self.cycles_elapsed,
+        })
     }
 
-    fn mnemonic_ldi(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let pc_offset = sign_extend(args & 0x1ff, 9);
-        self.register.write(
-            r0,
-            self.mem_read(self.mem_read(self.register.read(R::PC).wrapping_add(pc_offset))),
-        );
-        self.register.update_flag(r0);
-        Ok(())
-    }
-
-    fn mnemonic_ld(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let offset: u16 = sign_extend(args & 0x01ff, 9);
-        self.register.write(
-            r0,
-            self.mem_read(self.register.read(R::PC).wrapping_add(offset)),
-        );
-        self.register.update_flag(r0);
-        Ok(())
+    pub fn reg_store(&mut self, r: R, val: u16) {
+        self.register.write(r, val);
     }
 
-    fn mnemonic_st(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let offset: u16 = sign_extend(args & 0x01ff, 9);
-        self.mem_write(
-            self.register.read(R::PC).wrapping_add(offset),
-            self.register.read(r0),
-        );
-        Ok(())
+    pub fn reg_load(&self, r: R) -> u16 {
+        self.register.read(r)
     }
 
-    fn mnemonic_res(&mut self, _: u16) -> CycleResult {
-        Err("reserved opcode".to_string())
+    /// Reads from RAM, or from a keyboard/display register if `addr` lands
+    /// on one, via the same memory-mapped `Bus` that backs `VM`.
+    pub fn mem_read(&self, addr: u16) -> u16 {
+        self.bus.read(addr)
     }
 
-    fn mnemonic_jsr(&mut self, args: u16) -> CycleResult {
-        let mode = bit(args, 11);
-
-        self.register.write(R::_7, self.register.read(R::PC));
-
-        if mode == 1 {
-            self.register.write(
-                R::PC,
-                self.register
-                    .read(R::PC)
-                    .wrapping_add(sign_extend(args & 0x07ff, 11)),
-            );
-            return Ok(());
-        }
-
-        let r0: R = reg_2nd(args)?;
-        self.register.write(R::PC, self.register.read(r0));
-        Ok(())
+    /// Writes to RAM, or to a keyboard/display register if `addr` lands on
+    /// one, via the same memory-mapped `Bus` that backs `VM`.
+    pub fn mem_write(&mut self, addr: u16, val: u16) {
+        self.bus.write(addr, val);
     }
 
-    fn mnemonic_ldr(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        let offset = sign_extend(args & 0x3f, 6);
-
-        self.register.write(
-            r0,
-            self.mem_read(self.register.read(r1).wrapping_add(offset)),
-        );
-        self.register.update_flag(r0);
-        Ok(())
+    /// Serializes the complete machine state — registers, banked stack
+    /// pointers, the running flag, and all 64K of memory — into a blob that
+    /// `load_state` can restore exactly. See `snapshot` for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        crate::snapshot::save(&self.register, &self.bus, self.running)
     }
 
-    fn mnemonic_str(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        let offset = sign_extend(args & 0x01ff, 9);
-
-        self.mem_write(
-            self.register.read(r1).wrapping_add(offset),
-            self.register.read(r0),
-        );
+    /// Restores a state blob previously produced by `save_state`, replacing
+    /// this machine's registers, banked stack pointers, running flag, and
+    /// memory in place.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.running = crate::snapshot::load(bytes, &mut self.register, &mut self.bus, "CPU")?;
         Ok(())
     }
+}
 
-    fn mnemonic_rti(&mut self, _: u16) -> CycleResult {
-        if self.register.get_mode()? == Mode::Privilege {
-            let addr = self.register.read_incr(R::_6);
-            self.register.write(R::PC, self.mem_read(addr));
-
-            let addr = self.register.read_incr(R::_6);
-            self.register.write(R::PSR, self.mem_read(addr));
-            return Ok(());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::DSR;
+    use crate::register::PL;
+
+    #[test]
+    fn mem_read_routes_through_the_bus_to_device_registers() {
+        let cpu = CPU::new();
+        // The display is always ready for output, unlike plain RAM which
+        // starts zeroed.
+        assert_eq!(cpu.mem_read(DSR), 0x8000);
+    }
+
+    #[test]
+    fn service_irq_pushes_psr_and_pc_then_vectors_through_the_table() {
+        let mut cpu = CPU::new();
+        cpu.register.write(R::PC, 0x3000);
+        cpu.register.write(R::_6, 0x4000);
+        cpu.mem_write(0x0100 + 0x80, 0x1234);
+
+        cpu.service_irq(Irq {
+            priority: 4,
+            vector: 0x80,
+        })
+        .unwrap();
+
+        assert_eq!(cpu.reg_load(R::PC), 0x1234);
+        assert_eq!(cpu.reg_load(R::_6), 0x3ffe);
+        assert_eq!(cpu.mem_read(0x3ffe), 0x3000);
+        assert_eq!(cpu.register.get_level().unwrap() as u16, PL::_4 as u16);
+    }
+
+    #[test]
+    fn service_irq_from_user_mode_banks_onto_a_real_supervisor_stack_and_rti_restores_it() {
+        let mut cpu = CPU::new();
+        cpu.register.write(R::PC, 0x3000);
+        cpu.register.write(R::_6, 0x8000); // user stack pointer
+        cpu.register.set_mode(crate::register::Mode::User);
+        cpu.mem_write(0x0100 + 0x80, 0x1234);
+
+        cpu.service_irq(Irq {
+            priority: 4,
+            vector: 0x80,
+        })
+        .unwrap();
+
+        // Banked onto the default supervisor stack (0x3000), not the `0`
+        // that `saved_ssp` used to default to, so nothing was stomped at
+        // the 0xfffe/0xffff that bug used to corrupt.
+        assert_eq!(cpu.reg_load(R::_6), 0x2ffe);
+        assert_eq!(cpu.mem_read(0xfffe), 0);
+        assert_eq!(cpu.mem_read(0xffff), 0);
+        assert_eq!(cpu.reg_load(R::PC), 0x1234);
+
+        cpu.mem_write(0x1234, 0x8000); // RTI
+        cpu.tick().unwrap();
+
+        assert_eq!(cpu.reg_load(R::PC), 0x3000);
+        assert_eq!(cpu.reg_load(R::_6), 0x8000);
+        assert!(matches!(
+            cpu.register.get_mode().unwrap(),
+            crate::register::Mode::User
+        ));
+    }
+
+    #[test]
+    fn run_stops_once_max_cycles_is_reached() {
+        let mut cpu = CPU::new();
+        cpu.reg_store(R::PC, 0x3000);
+        for addr in 0x3000..0x3003 {
+            cpu.mem_write(addr, 0x1020); // ADD R0, R0, #0: 1 cycle each.
         }
 
-        // abort
-        // ...
+        let stats = cpu.run(2).unwrap();
 
-        Err("illegal RTI from user mode".to_string())
-    }
-
-    fn mnemonic_not(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        self.register.write(r0, !self.register.read(r1));
-        self.register.update_flag(r0);
-        Ok(())
-    }
-
-    fn mnemonic_sti(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let offset = sign_extend(args & 0x1ff, 9);
-        self.mem_write(
-            self.mem_read(self.register.read(R::PC).wrapping_add(offset)),
-            self.register.read(r0),
-        );
-        Ok(())
-    }
-
-    fn mnemonic_jmp(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_2nd(args)?;
-        self.register.write(R::PC, self.register.read(r0));
-        Ok(())
-    }
-
-    fn mnemonic_lea(&mut self, args: u16) -> CycleResult {
-        let r0: R = reg_1st(args)?;
-        let offset = sign_extend(args & 0x01ff, 9);
-        self.register
-            .write(r0, self.register.read(R::PC).wrapping_add(offset));
-        self.register.update_flag(r0);
-        Ok(())
-    }
-
-    fn mnemonic_trap(&mut self, args: u16) -> CycleResult {
-        self.register.write(R::_7, self.register.read(R::PC));
-        self.register.write(R::PC, self.mem_read(args & 0x00ff));
-        Ok(())
+        assert_eq!(stats.instructions, 2);
+        assert_eq!(stats.cycles, 2);
+        assert_eq!(cpu.instructions_executed(), 2);
+        assert_eq!(cpu.cycles_elapsed(), 2);
     }
 }
@@ -0,0 +1,294 @@
+use crate::bit;
+use crate::cpu::CPU;
+use crate::register::{Flag, R};
+use crate::vm::VM;
+use std::collections::HashSet;
+
+/// Why a debug run stopped.
+#[derive(Debug, PartialEq)]
+pub enum StopReason {
+    Halted,
+    Breakpoint(u16),
+    BudgetExhausted,
+}
+
+/// A point-in-time snapshot of the machine for inspection: the eight GP
+/// registers, PC, the PSR decoded into mode/priority/flag, and a hex window
+/// of memory around an address.
+pub struct MachineState {
+    pub gpr: [u16; 8],
+    pub pc: u16,
+    pub psr: u16,
+    pub mode: &'static str,
+    pub priority: u16,
+    pub flag: &'static str,
+    pub memory: Vec<(u16, u16)>,
+}
+
+/// Builds a `MachineState` from raw register/memory reads, shared by every
+/// `Debuggable` impl's `dump` so the PSR-decoding and memory-windowing logic
+/// lives in one place. `window` is caller-controlled and up to `u16::MAX`,
+/// so it's widened before doubling rather than risking a `u16` overflow.
+fn dump_state(gpr: [u16; 8], pc: u16, psr: u16, around: u16, window: u16, read: impl Fn(u16) -> u16) -> MachineState {
+    let mode = if bit(psr, 15) == 0 { "Privilege" } else { "User" };
+    let priority = (psr >> 7) & 7;
+    let flag = match psr & 7 {
+        x if x == Flag::Positive as u16 => "P",
+        x if x == Flag::Zero as u16 => "Z",
+        x if x == Flag::Negative as u16 => "N",
+        _ => "?",
+    };
+
+    let start = around.wrapping_sub(window);
+    let span = u32::from(window) * 2;
+    let memory = (0..=span)
+        .map(|i| {
+            let addr = start.wrapping_add(i as u16);
+            (addr, read(addr))
+        })
+        .collect();
+
+    MachineState {
+        gpr,
+        pc,
+        psr,
+        mode,
+        priority,
+        flag,
+        memory,
+    }
+}
+
+/// A step-debugger layer over `CPU`/`VM`: register breakpoints, run until
+/// the next one (or an instruction budget runs out), single-step, and
+/// inspect state.
+pub trait Debuggable {
+    fn add_breakpoint(&mut self, addr: u16);
+    fn remove_breakpoint(&mut self, addr: u16);
+    fn breakpoints(&self) -> &HashSet<u16>;
+    fn step(&mut self) -> Result<(), String>;
+    fn run(&mut self, max_instructions: u64) -> Result<StopReason, String>;
+    fn dump(&self, around: u16, window: u16) -> MachineState;
+}
+
+impl Debuggable for VM {
+    fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    fn breakpoints(&self) -> &HashSet<u16> {
+        &self.breakpoints
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.next()
+    }
+
+    // Stepping over a breakpoint already reported last call is what lets a
+    // second `run` make progress instead of re-reporting it forever; see
+    // the longer note on `CPU`'s impl.
+    fn run(&mut self, max_instructions: u64) -> Result<StopReason, String> {
+        for _ in 0..max_instructions {
+            if !self.is_running() {
+                self.last_breakpoint_hit = None;
+                return Ok(StopReason::Halted);
+            }
+
+            let pc = self.reg_load(R::PC);
+            if self.breakpoints.contains(&pc) && self.last_breakpoint_hit != Some(pc) {
+                self.last_breakpoint_hit = Some(pc);
+                return Ok(StopReason::Breakpoint(pc));
+            }
+            self.last_breakpoint_hit = None;
+
+            self.next()?;
+        }
+
+        Ok(StopReason::BudgetExhausted)
+    }
+
+    fn dump(&self, around: u16, window: u16) -> MachineState {
+        let gpr = [
+            self.reg_load(R::_0),
+            self.reg_load(R::_1),
+            self.reg_load(R::_2),
+            self.reg_load(R::_3),
+            self.reg_load(R::_4),
+            self.reg_load(R::_5),
+            self.reg_load(R::_6),
+            self.reg_load(R::_7),
+        ];
+        dump_state(gpr, self.reg_load(R::PC), self.reg_load(R::PSR), around, window, |addr| {
+            self.read_memory(addr)
+        })
+    }
+}
+
+impl Debuggable for CPU {
+    fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    fn breakpoints(&self) -> &HashSet<u16> {
+        &self.breakpoints
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.tick().map(|_| ())
+    }
+
+    // `CPU` already has an inherent, cycle-budgeted `run` (see `CPU::run`);
+    // this instruction-budgeted one is only reachable as `Debuggable::run`.
+    //
+    // Once this returns `Breakpoint(pc)`, `pc` is still the current PC, so a
+    // naive re-check at the top of the next call would report the same
+    // breakpoint again without ever executing past it. `last_breakpoint_hit`
+    // remembers the PC we last stopped at: the next call steps over it
+    // first (like `gdb`'s `continue` stepping over the current breakpoint),
+    // then resumes the normal check-then-step loop so a breakpoint inside a
+    // loop body still fires again on the next pass.
+    fn run(&mut self, max_instructions: u64) -> Result<StopReason, String> {
+        for _ in 0..max_instructions {
+            if !self.is_running() {
+                self.last_breakpoint_hit = None;
+                return Ok(StopReason::Halted);
+            }
+
+            let pc = self.reg_load(R::PC);
+            if self.breakpoints.contains(&pc) && self.last_breakpoint_hit != Some(pc) {
+                self.last_breakpoint_hit = Some(pc);
+                return Ok(StopReason::Breakpoint(pc));
+            }
+            self.last_breakpoint_hit = None;
+
+            self.tick()?;
+        }
+
+        Ok(StopReason::BudgetExhausted)
+    }
+
+    fn dump(&self, around: u16, window: u16) -> MachineState {
+        let gpr = [
+            self.reg_load(R::_0),
+            self.reg_load(R::_1),
+            self.reg_load(R::_2),
+            self.reg_load(R::_3),
+            self.reg_load(R::_4),
+            self.reg_load(R::_5),
+            self.reg_load(R::_6),
+            self.reg_load(R::_7),
+        ];
+        dump_state(gpr, self.reg_load(R::PC), self.reg_load(R::PSR), around, window, |addr| {
+            self.mem_read(addr)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Args;
+
+    // ADD R0, R0, #0: a one-word no-op that leaves memory undisturbed.
+    const NOP: u16 = 0x1020;
+    // TRAP x25: HALT.
+    const HALT: u16 = 0xf025;
+
+    fn vm_with_program(origin: u16, program: &[u16]) -> VM {
+        let mut vm = VM::boot(Args {
+            offset: origin,
+            image: None,
+        })
+        .unwrap();
+        for (i, &word) in program.iter().enumerate() {
+            vm.write_memory(origin.wrapping_add(i as u16), word);
+        }
+        vm
+    }
+
+    #[test]
+    fn run_stops_at_a_breakpoint_before_executing_it() {
+        let mut vm = vm_with_program(0x3000, &[NOP, NOP, NOP]);
+        vm.add_breakpoint(0x3001);
+
+        let reason = vm.run(10).unwrap();
+
+        assert_eq!(reason, StopReason::Breakpoint(0x3001));
+        assert_eq!(vm.reg_load(R::PC), 0x3001);
+    }
+
+    #[test]
+    fn run_steps_over_a_breakpoint_already_reported_before_resuming() {
+        let mut vm = vm_with_program(0x3000, &[NOP, NOP, HALT]);
+        vm.add_breakpoint(0x3001);
+
+        assert_eq!(vm.run(10).unwrap(), StopReason::Breakpoint(0x3001));
+        assert_eq!(vm.reg_load(R::PC), 0x3001);
+
+        // A second `run` must not just re-report the same breakpoint with
+        // zero forward progress: it steps past 0x3001 first, then runs
+        // until HALT (there is nothing else to stop it).
+        assert_eq!(vm.run(10).unwrap(), StopReason::Halted);
+        assert_eq!(vm.reg_load(R::PC), 0x3003);
+    }
+
+    #[test]
+    fn dump_does_not_overflow_with_a_large_window() {
+        let vm = vm_with_program(0x3000, &[NOP]);
+        let state = vm.dump(0x4000, 40000);
+        assert_eq!(state.memory.len(), 80001);
+    }
+
+    fn cpu_with_program(origin: u16, program: &[u16]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.reg_store(R::PC, origin);
+        for (i, &word) in program.iter().enumerate() {
+            cpu.mem_write(origin.wrapping_add(i as u16), word);
+        }
+        cpu
+    }
+
+    #[test]
+    fn cpu_run_stops_at_a_breakpoint_before_executing_it() {
+        let mut cpu = cpu_with_program(0x3000, &[NOP, NOP, NOP]);
+        cpu.add_breakpoint(0x3001);
+
+        // CPU already has an inherent, cycle-budgeted `run`, so the
+        // debugger's instruction-budgeted one needs disambiguating.
+        let reason = Debuggable::run(&mut cpu, 10).unwrap();
+
+        assert_eq!(reason, StopReason::Breakpoint(0x3001));
+        assert_eq!(cpu.reg_load(R::PC), 0x3001);
+    }
+
+    #[test]
+    fn cpu_run_steps_over_a_breakpoint_already_reported_before_resuming() {
+        let mut cpu = cpu_with_program(0x3000, &[NOP, NOP, HALT]);
+        cpu.add_breakpoint(0x3001);
+
+        let reason = Debuggable::run(&mut cpu, 10).unwrap();
+        assert_eq!(reason, StopReason::Breakpoint(0x3001));
+        assert_eq!(cpu.reg_load(R::PC), 0x3001);
+
+        // Same fix as the VM test above: a second `run` steps past 0x3001
+        // first instead of re-reporting it forever.
+        let reason = Debuggable::run(&mut cpu, 10).unwrap();
+        assert_eq!(reason, StopReason::Halted);
+        assert_eq!(cpu.reg_load(R::PC), 0x3003);
+    }
+
+    #[test]
+    fn cpu_dump_does_not_overflow_with_a_large_window() {
+        let cpu = cpu_with_program(0x3000, &[NOP]);
+        let state = cpu.dump(0x4000, 40000);
+        assert_eq!(state.memory.len(), 80001);
+    }
+}
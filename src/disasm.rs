@@ -0,0 +1,214 @@
+use crate::exec::OpCode;
+use crate::{bit, reg_1st, reg_2nd, sign_extend};
+use std::convert::TryInto;
+
+fn reg_name(r: crate::register::R) -> &'static str {
+    use crate::register::R;
+    match r {
+        R::_0 => "R0",
+        R::_1 => "R1",
+        R::_2 => "R2",
+        R::_3 => "R3",
+        R::_4 => "R4",
+        R::_5 => "R5",
+        R::_6 => "R6",
+        R::_7 => "R7",
+        R::PC => "PC",
+        R::PSR => "PSR",
+    }
+}
+
+fn pc_relative_target(addr: u16, instr: u16, bitcount: u16) -> u16 {
+    let offset = sign_extend(instr & ((1 << bitcount) - 1), bitcount);
+    addr.wrapping_add(1).wrapping_add(offset)
+}
+
+fn trap_mnemonic(vector: u16) -> String {
+    match vector {
+        0x20 => "GETC".to_string(),
+        0x21 => "OUT".to_string(),
+        0x22 => "PUTS".to_string(),
+        0x23 => "IN".to_string(),
+        0x24 => "PUTSP".to_string(),
+        0x25 => "HALT".to_string(),
+        _ => format!("TRAP x{:02x}", vector),
+    }
+}
+
+/// Decodes a single instruction word into its canonical LC-3 assembly text.
+/// `addr` is where the word lives, used to resolve PC-relative targets.
+pub fn disassemble(instr: u16, addr: u16) -> Result<String, String> {
+    let opcode: OpCode = (instr >> 12).try_into()?;
+
+    let text = match opcode {
+        OpCode::BR => {
+            let n = bit(instr, 11);
+            let z = bit(instr, 10);
+            let p = bit(instr, 9);
+            let cond = if n == 0 && z == 0 && p == 0 {
+                "nzp".to_string()
+            } else {
+                let mut cond = String::new();
+                if n == 1 {
+                    cond.push('n');
+                }
+                if z == 1 {
+                    cond.push('z');
+                }
+                if p == 1 {
+                    cond.push('p');
+                }
+                cond
+            };
+            let target = pc_relative_target(addr, instr, 9);
+            format!("BR{} 0x{:04x}", cond, target)
+        }
+
+        OpCode::ADD | OpCode::AND => {
+            let mnemonic = if matches!(opcode, OpCode::ADD) {
+                "ADD"
+            } else {
+                "AND"
+            };
+            let r0 = reg_1st(instr)?;
+            let r1 = reg_2nd(instr)?;
+            if bit(instr, 5) == 1 {
+                let imm = sign_extend(instr & 0x1f, 5) as i16;
+                format!("{} {}, {}, #{}", mnemonic, reg_name(r0), reg_name(r1), imm)
+            } else {
+                let r2 = (instr & 0x7).try_into()?;
+                format!(
+                    "{} {}, {}, {}",
+                    mnemonic,
+                    reg_name(r0),
+                    reg_name(r1),
+                    reg_name(r2)
+                )
+            }
+        }
+
+        OpCode::LD => format!(
+            "LD {}, 0x{:04x}",
+            reg_name(reg_1st(instr)?),
+            pc_relative_target(addr, instr, 9)
+        ),
+
+        OpCode::ST => format!(
+            "ST {}, 0x{:04x}",
+            reg_name(reg_1st(instr)?),
+            pc_relative_target(addr, instr, 9)
+        ),
+
+        OpCode::LDI => format!(
+            "LDI {}, 0x{:04x}",
+            reg_name(reg_1st(instr)?),
+            pc_relative_target(addr, instr, 9)
+        ),
+
+        OpCode::STI => format!(
+            "STI {}, 0x{:04x}",
+            reg_name(reg_1st(instr)?),
+            pc_relative_target(addr, instr, 9)
+        ),
+
+        OpCode::LEA => format!(
+            "LEA {}, 0x{:04x}",
+            reg_name(reg_1st(instr)?),
+            pc_relative_target(addr, instr, 9)
+        ),
+
+        OpCode::LDR => {
+            let offset = sign_extend(instr & 0x3f, 6) as i16;
+            format!(
+                "LDR {}, {}, #{}",
+                reg_name(reg_1st(instr)?),
+                reg_name(reg_2nd(instr)?),
+                offset
+            )
+        }
+
+        OpCode::STR => {
+            let offset = sign_extend(instr & 0x3f, 6) as i16;
+            format!(
+                "STR {}, {}, #{}",
+                reg_name(reg_1st(instr)?),
+                reg_name(reg_2nd(instr)?),
+                offset
+            )
+        }
+
+        OpCode::NOT => format!(
+            "NOT {}, {}",
+            reg_name(reg_1st(instr)?),
+            reg_name(reg_2nd(instr)?)
+        ),
+
+        OpCode::JSR => {
+            if bit(instr, 11) == 1 {
+                format!("JSR 0x{:04x}", pc_relative_target(addr, instr, 11))
+            } else {
+                format!("JSRR {}", reg_name(reg_2nd(instr)?))
+            }
+        }
+
+        OpCode::JMP => {
+            let r = reg_2nd(instr)?;
+            match r {
+                crate::register::R::_7 => "RET".to_string(),
+                _ => format!("JMP {}", reg_name(r)),
+            }
+        }
+
+        OpCode::RTI => "RTI".to_string(),
+
+        OpCode::TRAP => trap_mnemonic(instr & 0x00ff),
+
+        OpCode::RES => "RESERVED".to_string(),
+    };
+
+    Ok(text)
+}
+
+/// Disassembles a contiguous run of memory words, pairing each with its
+/// address. Handy for backing a debugger listing view or an `objdump`-style
+/// CLI mode.
+pub fn disassemble_range(start_addr: u16, words: &[u16]) -> Vec<(u16, String)> {
+    words
+        .iter()
+        .enumerate()
+        .map(|(i, &word)| {
+            let addr = start_addr.wrapping_add(i as u16);
+            let text = disassemble(word, addr).unwrap_or_else(|e| format!(".WORD 0x{:04x} ; {}", word, e));
+            (addr, text)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_add_with_immediate() {
+        // ADD R0, R1, #3
+        assert_eq!(disassemble(0x1063, 0x3000).unwrap(), "ADD R0, R1, #3");
+    }
+
+    #[test]
+    fn decodes_trap_vectors_to_their_mnemonics() {
+        assert_eq!(disassemble(0xf025, 0x3000).unwrap(), "HALT");
+        assert_eq!(disassemble(0xf022, 0x3000).unwrap(), "PUTS");
+    }
+
+    #[test]
+    fn decodes_pc_relative_branch_target() {
+        // BR (always, n/z/p all clear), PCoffset9 = 1, so the target is
+        // addr + 1 + 1.
+        assert_eq!(disassemble(0x0001, 0x3000).unwrap(), "BRnzp 0x3002");
+    }
+
+    #[test]
+    fn decodes_the_reserved_opcode_as_reserved() {
+        assert_eq!(disassemble(0xd000, 0x3000).unwrap(), "RESERVED");
+    }
+}
@@ -0,0 +1,316 @@
+//! Shared fetch/decode/execute core between `CPU` and `VM`: both dispatch
+//! the same sixteen opcodes against the same `Register`/`Bus` pair and only
+//! differ in whether they bill cycles, so the instruction bodies live here
+//! once instead of twice — the same reasoning that already moved the TRAP
+//! bodies into `trap` and the interrupt-entry sequence into `interrupt`.
+//! Billing cycles and servicing a pending IRQ are still each machine's own
+//! job; `step` only covers one fetch/decode/execute.
+use crate::bus::{Addressable, Bus};
+use crate::register::{Flag, Mode, Register, R};
+use crate::{bit, reg_1st, reg_2nd, sign_extend};
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use std::convert::{TryFrom, TryInto};
+
+/// Number of machine cycles an instruction consumes, or an error.
+pub type CycleResult = Result<u32, String>;
+
+// Simplified cycle costs: register-only instructions complete in one cycle,
+// a single memory access adds a cycle, and an indirect access (two memory
+// round-trips, as in LDI/STI) adds two.
+pub(crate) const CYCLES_REGISTER_ONLY: u32 = 1;
+pub(crate) const CYCLES_MEMORY_ACCESS: u32 = 2;
+pub(crate) const CYCLES_INDIRECT_MEMORY_ACCESS: u32 = 3;
+
+#[derive(Debug, FromPrimitive)]
+#[repr(u16)]
+pub enum OpCode {
+    BR,   // 0000
+    ADD,  // 0001
+    LD,   // 0010
+    ST,   // 0011
+    JSR,  // 0100
+    AND,  // 0101
+    LDR,  // 0110
+    STR,  // 0111
+    RTI,  // 1000
+    NOT,  // 1001
+    LDI,  // 1010
+    STI,  // 1011
+    JMP,  // 1100 // JMP R7 == RET
+    RES,  // 1101 // reserved
+    LEA,  // 1110
+    TRAP, // 1111
+}
+
+impl TryFrom<u16> for OpCode {
+    type Error = String;
+
+    fn try_from(val: u16) -> Result<Self, Self::Error> {
+        OpCode::from_u16(val).ok_or(format!("unknown opcode `{}`", val))
+    }
+}
+
+/// Fetches the instruction at `PC`, decodes it, and executes it against
+/// `register`/`bus`, returning the cycles it consumed. `running` is cleared
+/// by TRAP HALT (x25) and by an illegal RTI from User mode. Servicing a
+/// pending IRQ before the fetch is each caller's own job (see
+/// `CPU::tick`/`VM::next`), since only `CPU` bills the extra cycles for it.
+pub(crate) fn step(register: &mut Register, bus: &mut Bus, running: &mut bool) -> CycleResult {
+    let boot_addr = register.read_incr(R::PC);
+    let instr: u16 = bus.read(boot_addr);
+    let opcode: OpCode = (instr >> 12).try_into()?;
+
+    match opcode {
+        OpCode::BR => mnemonic_br(register, instr),
+        OpCode::ADD => mnemonic_add(register, instr),
+        OpCode::LD => mnemonic_ld(register, bus, instr),
+        OpCode::ST => mnemonic_st(register, bus, instr),
+        OpCode::JSR => mnemonic_jsr(register, instr),
+        OpCode::AND => mnemonic_and(register, instr),
+        OpCode::LDR => mnemonic_ldr(register, bus, instr),
+        OpCode::STR => mnemonic_str(register, bus, instr),
+        OpCode::RTI => mnemonic_rti(register, bus, running, instr),
+        OpCode::NOT => mnemonic_not(register, instr),
+        OpCode::LDI => mnemonic_ldi(register, bus, instr),
+        OpCode::STI => mnemonic_sti(register, bus, instr),
+        OpCode::JMP => mnemonic_jmp(register, instr),
+        OpCode::RES => mnemonic_res(instr),
+        OpCode::LEA => mnemonic_lea(register, instr),
+        OpCode::TRAP => mnemonic_trap(register, bus, running, instr),
+    }
+}
+
+fn mnemonic_br(register: &mut Register, args: u16) -> CycleResult {
+    let offset = sign_extend(args & 0x1ff, 9);
+
+    let nzp = (args >> 9) & 0x7;
+    if nzp == 0 {
+        register.write(R::PC, register.read(R::PC).wrapping_add(offset));
+        return Ok(CYCLES_REGISTER_ONLY);
+    }
+
+    let n: bool = bit(args, 11) == 1;
+    let z: bool = bit(args, 10) == 1;
+    let p: bool = bit(args, 9) == 1;
+
+    let flag = register.get_flag()?;
+
+    if (n && flag == Flag::Negative) || (z && flag == Flag::Zero) || (p && flag == Flag::Positive)
+    {
+        register.write(R::PC, register.read(R::PC).wrapping_add(offset));
+    }
+
+    Ok(CYCLES_REGISTER_ONLY)
+}
+
+fn mnemonic_imm5_or_sr2<F>(register: &mut Register, args: u16, func: F) -> CycleResult
+where
+    F: Fn(u16, u16) -> u16,
+{
+    let r0: R = reg_1st(args)?;
+    let r1: R = reg_2nd(args)?;
+    let imm_flag: u16 = bit(args, 5);
+
+    if imm_flag == 1 {
+        register.write(r0, func(register.read(r1), sign_extend(args & 0x1f, 5)));
+    } else {
+        let r2: R = (args & 0x7).try_into()?;
+        register.write(r0, func(register.read(r1), register.read(r2)));
+    }
+    register.update_flag(r0);
+    Ok(CYCLES_REGISTER_ONLY)
+}
+
+fn mnemonic_add(register: &mut Register, args: u16) -> CycleResult {
+    mnemonic_imm5_or_sr2(register, args, |r1, r2| r1.wrapping_add(r2))
+}
+
+fn mnemonic_and(register: &mut Register, args: u16) -> CycleResult {
+    mnemonic_imm5_or_sr2(register, args, |r1, r2| r1 & r2)
+}
+
+fn mnemonic_ldi(register: &mut Register, bus: &mut impl Addressable, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let pc_offset = sign_extend(args & 0x1ff, 9);
+    let indirect = bus.read(register.read(R::PC).wrapping_add(pc_offset));
+    register.write(r0, bus.read(indirect));
+    register.update_flag(r0);
+    Ok(CYCLES_INDIRECT_MEMORY_ACCESS)
+}
+
+fn mnemonic_ld(register: &mut Register, bus: &mut impl Addressable, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let offset: u16 = sign_extend(args & 0x01ff, 9);
+    register.write(r0, bus.read(register.read(R::PC).wrapping_add(offset)));
+    register.update_flag(r0);
+    Ok(CYCLES_MEMORY_ACCESS)
+}
+
+fn mnemonic_st(register: &mut Register, bus: &mut impl Addressable, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let offset: u16 = sign_extend(args & 0x01ff, 9);
+    bus.write(register.read(R::PC).wrapping_add(offset), register.read(r0));
+    Ok(CYCLES_MEMORY_ACCESS)
+}
+
+fn mnemonic_res(_: u16) -> CycleResult {
+    Err("reserved opcode".to_string())
+}
+
+fn mnemonic_jsr(register: &mut Register, args: u16) -> CycleResult {
+    let mode = bit(args, 11);
+
+    register.write(R::_7, register.read(R::PC));
+
+    if mode == 1 {
+        register.write(
+            R::PC,
+            register
+                .read(R::PC)
+                .wrapping_add(sign_extend(args & 0x07ff, 11)),
+        );
+        return Ok(CYCLES_REGISTER_ONLY);
+    }
+
+    let r0: R = reg_2nd(args)?;
+    register.write(R::PC, register.read(r0));
+    Ok(CYCLES_REGISTER_ONLY)
+}
+
+fn mnemonic_ldr(register: &mut Register, bus: &mut impl Addressable, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let r1: R = reg_2nd(args)?;
+    let offset = sign_extend(args & 0x3f, 6);
+
+    register.write(r0, bus.read(register.read(r1).wrapping_add(offset)));
+    register.update_flag(r0);
+    Ok(CYCLES_MEMORY_ACCESS)
+}
+
+fn mnemonic_str(register: &mut Register, bus: &mut impl Addressable, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let r1: R = reg_2nd(args)?;
+    let offset = sign_extend(args & 0x01ff, 9);
+
+    bus.write(register.read(r1).wrapping_add(offset), register.read(r0));
+    Ok(CYCLES_MEMORY_ACCESS)
+}
+
+fn mnemonic_rti(
+    register: &mut Register,
+    bus: &mut impl Addressable,
+    running: &mut bool,
+    _: u16,
+) -> CycleResult {
+    if register.get_mode()? == Mode::Privilege {
+        let addr = register.read_incr(R::_6);
+        register.write(R::PC, bus.read(addr));
+
+        let addr = register.read_incr(R::_6);
+        register.write(R::PSR, bus.read(addr));
+
+        if register.get_mode()? == Mode::User {
+            register.leave_supervisor_stack();
+        }
+
+        return Ok(CYCLES_INDIRECT_MEMORY_ACCESS);
+    }
+
+    *running = false;
+    Err("illegal RTI from user mode".to_string())
+}
+
+fn mnemonic_not(register: &mut Register, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let r1: R = reg_2nd(args)?;
+    register.write(r0, !register.read(r1));
+    register.update_flag(r0);
+    Ok(CYCLES_REGISTER_ONLY)
+}
+
+fn mnemonic_sti(register: &mut Register, bus: &mut impl Addressable, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let offset = sign_extend(args & 0x1ff, 9);
+    let indirect = bus.read(register.read(R::PC).wrapping_add(offset));
+    bus.write(indirect, register.read(r0));
+    Ok(CYCLES_INDIRECT_MEMORY_ACCESS)
+}
+
+fn mnemonic_jmp(register: &mut Register, args: u16) -> CycleResult {
+    let r0: R = reg_2nd(args)?;
+    register.write(R::PC, register.read(r0));
+    Ok(CYCLES_REGISTER_ONLY)
+}
+
+fn mnemonic_lea(register: &mut Register, args: u16) -> CycleResult {
+    let r0: R = reg_1st(args)?;
+    let offset = sign_extend(args & 0x01ff, 9);
+    register.write(r0, register.read(R::PC).wrapping_add(offset));
+    register.update_flag(r0);
+    Ok(CYCLES_REGISTER_ONLY)
+}
+
+fn mnemonic_trap(
+    register: &mut Register,
+    bus: &mut Bus,
+    running: &mut bool,
+    args: u16,
+) -> CycleResult {
+    register.write(R::_7, register.read(R::PC));
+
+    let cycles = match args & 0x00ff {
+        0x20 => {
+            crate::trap::trap_getc(register, || bus.blocking_read_byte())?;
+            CYCLES_REGISTER_ONLY
+        }
+        0x21 => {
+            crate::trap::trap_out(register)?;
+            CYCLES_REGISTER_ONLY
+        }
+        0x22 => {
+            let words_read = crate::trap::trap_puts(register, |addr| bus.read(addr))?;
+            CYCLES_REGISTER_ONLY + words_read * CYCLES_MEMORY_ACCESS
+        }
+        0x23 => {
+            crate::trap::trap_in(register, || bus.blocking_read_byte())?;
+            CYCLES_REGISTER_ONLY
+        }
+        0x24 => {
+            let words_read = crate::trap::trap_putsp(register, |addr| bus.read(addr))?;
+            CYCLES_REGISTER_ONLY + words_read * CYCLES_MEMORY_ACCESS
+        }
+        0x25 => {
+            *running = false;
+            CYCLES_REGISTER_ONLY
+        }
+        vector => {
+            register.write(R::PC, bus.read(vector));
+            CYCLES_MEMORY_ACCESS
+        }
+    };
+
+    Ok(cycles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn trap_getc_reads_through_the_bus_not_a_second_competing_stdin_read() {
+        let (tx, rx) = mpsc::channel();
+        let mut bus = Bus::with_keyboard_channel(rx);
+        tx.send(b'Q').unwrap();
+
+        let mut register = Register::new();
+        register.write(R::PC, 0x3000);
+        bus.write(0x3000, 0xf020); // TRAP x20 (GETC)
+
+        let mut running = true;
+        step(&mut register, &mut bus, &mut running).unwrap();
+
+        assert_eq!(register.read(R::_0), b'Q' as u16);
+    }
+}
@@ -0,0 +1,39 @@
+//! The LC-3 interrupt-entry sequence, shared between `CPU` and `VM`: bank
+//! onto the supervisor stack if coming from User mode, push PSR then PC (so
+//! RTI's PC-then-PSR pop order lines up), raise PL to the device's level,
+//! and vector through `0x0100 + vector`.
+use crate::bus::{Addressable, Irq};
+use crate::register::{Mode, Register, PL, R};
+use num_traits::FromPrimitive;
+
+pub(crate) fn service_irq(
+    register: &mut Register,
+    bus: &mut impl Addressable,
+    irq: Irq,
+) -> Result<(), String> {
+    if register.get_mode()? == Mode::User {
+        register.enter_supervisor_stack();
+    }
+
+    let psr = register.read(R::PSR);
+    let pc = register.read(R::PC);
+    push_word(register, bus, psr);
+    push_word(register, bus, pc);
+
+    register.set_mode(Mode::Privilege);
+    let pl = PL::from_u16(irq.priority)
+        .ok_or_else(|| format!("invalid interrupt priority `{}`", irq.priority))?;
+    register.set_level(pl);
+
+    let vector_addr = 0x0100u16.wrapping_add(irq.vector);
+    let entry = bus.read(vector_addr);
+    register.write(R::PC, entry);
+
+    Ok(())
+}
+
+fn push_word(register: &mut Register, bus: &mut impl Addressable, val: u16) {
+    let sp = register.read(R::_6).wrapping_sub(1);
+    register.write(R::_6, sp);
+    bus.write(sp, val);
+}
@@ -1,5 +1,13 @@
+pub mod bus;
 pub mod cpu;
+pub mod debugger;
+mod exec;
+mod interrupt;
+#[cfg(feature = "disasm")]
+pub mod disasm;
 pub mod register;
+mod snapshot;
+mod trap;
 pub mod vm;
 
 use register::R;
@@ -3,9 +3,14 @@ use lc3::vm::{Args, VM};
 const PC_START: u16 = 0x3000;
 
 fn main() {
+    // First positional argument, if any, is the path to a `.obj` image to
+    // load at boot; with no argument the VM starts with empty memory at
+    // `PC_START`, as before.
+    let image = std::env::args().nth(1);
+
     let mut vm = VM::boot(Args {
         offset: PC_START,
-        image: None,
+        image,
     }).unwrap();
 
     while vm.is_running() {
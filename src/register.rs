@@ -62,7 +62,7 @@ impl TryFrom<u16> for PL {
     }
 }
 
-#[derive(FromPrimitive)]
+#[derive(FromPrimitive, PartialEq)]
 #[repr(u16)]
 pub enum Mode {
     Privilege = 0,
@@ -77,19 +77,34 @@ impl TryFrom<u16> for Mode {
     }
 }
 
-pub struct Register([u16; 10]);
+/// The conventional LC-3 supervisor stack base: `Register::new`'s default
+/// `saved_ssp`, so the first interrupt taken from User mode banks onto a
+/// real stack instead of `0`.
+const DEFAULT_SUPERVISOR_STACK: u16 = 0x3000;
+
+pub struct Register {
+    gpr: [u16; 10],
+    // Hidden stack pointers, not addressable as registers: R6 is banked
+    // between these depending on the current privilege mode.
+    saved_usp: u16,
+    saved_ssp: u16,
+}
 
 impl Register {
     pub fn new() -> Self {
-        Register([0; 10])
+        Register {
+            gpr: [0; 10],
+            saved_usp: 0,
+            saved_ssp: DEFAULT_SUPERVISOR_STACK,
+        }
     }
 
     pub fn write(&mut self, r: R, val: u16) {
-        self.0[r as usize] = val;
+        self.gpr[r as usize] = val;
     }
 
     pub fn read(&self, r: R) -> u16 {
-        self.0[r as usize]
+        self.gpr[r as usize]
     }
 
     pub fn read_incr(&mut self, r: R) -> u16 {
@@ -99,7 +114,7 @@ impl Register {
     }
 
     pub fn update_flag(&mut self, r: R) {
-        match self.0[r as usize] {
+        match self.gpr[r as usize] {
             0 => self.set_flag(Flag::Zero),
             x if x >> 15 == 1 => self.set_flag(Flag::Negative),
             _ => self.set_flag(Flag::Positive),
@@ -119,7 +134,7 @@ impl Register {
     }
 
     pub fn get_level(&self) -> Result<PL, String> {
-        ((self.read(R::PSR) >> 7) & 7).try_into()
+        self.read(R::PSR).try_into()
     }
 
     pub fn set_mode(&mut self, m: Mode) {
@@ -127,10 +142,54 @@ impl Register {
     }
 
     pub fn get_mode(&self) -> Result<Mode, String> {
-        (self.read(R::PSR) >> 15).try_into()
+        self.read(R::PSR).try_into()
     }
 
     pub fn incr(&mut self, r: R) {
         self.write(r, self.read(r) + 1);
     }
+
+    /// Banks R6 over to the supervisor stack pointer, stashing the current
+    /// (user) one. Called on interrupt/exception entry from User mode.
+    pub fn enter_supervisor_stack(&mut self) {
+        self.saved_usp = self.read(R::_6);
+        self.write(R::_6, self.saved_ssp);
+    }
+
+    /// Banks R6 back to the user stack pointer, stashing the current
+    /// (supervisor) one. Called by RTI when returning to User mode.
+    pub fn leave_supervisor_stack(&mut self) {
+        self.saved_ssp = self.read(R::_6);
+        self.write(R::_6, self.saved_usp);
+    }
+
+    /// The banked stack pointers not reachable through `read`/`write`, for
+    /// state snapshotting.
+    pub(crate) fn banked_stacks(&self) -> (u16, u16) {
+        (self.saved_usp, self.saved_ssp)
+    }
+
+    pub(crate) fn restore_banked_stacks(&mut self, saved_usp: u16, saved_ssp: u16) {
+        self.saved_usp = saved_usp;
+        self.saved_ssp = saved_ssp;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_level_round_trips_through_set_level() {
+        let mut register = Register::new();
+        register.set_level(PL::_4);
+        assert_eq!(register.get_level().unwrap() as u16, PL::_4 as u16);
+    }
+
+    #[test]
+    fn get_mode_round_trips_through_set_mode() {
+        let mut register = Register::new();
+        register.set_mode(Mode::User);
+        assert!(matches!(register.get_mode().unwrap(), Mode::User));
+    }
 }
@@ -0,0 +1,140 @@
+//! Shared binary format behind `CPU::save_state`/`load_state` and
+//! `VM::save_state`/`load_state`: a magic tag and version (so a future
+//! format change can be rejected instead of misread), the running flag,
+//! the ten register words in `REGISTER_ORDER`, the two banked stack
+//! pointers, and finally the full 64K memory image — all big-endian,
+//! matching the byte order `load_image` already uses for `.obj` files.
+use crate::bus::Bus;
+use crate::register::{Register, R};
+
+const MAGIC: &[u8; 4] = b"LC3S";
+const VERSION: u16 = 1;
+
+const REGISTER_ORDER: [R; 10] = [
+    R::_0,
+    R::_1,
+    R::_2,
+    R::_3,
+    R::_4,
+    R::_5,
+    R::_6,
+    R::_7,
+    R::PC,
+    R::PSR,
+];
+
+/// Serializes the complete machine state — registers, banked stack
+/// pointers, the running flag, and all 64K of memory — into a blob that
+/// `load` can restore exactly.
+pub(crate) fn save(register: &Register, bus: &Bus, running: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(7 + 24 + (u16::MAX as usize + 1) * 2);
+
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&VERSION.to_be_bytes());
+    buf.push(running as u8);
+
+    for r in REGISTER_ORDER {
+        buf.extend_from_slice(&register.read(r).to_be_bytes());
+    }
+
+    let (usp, ssp) = register.banked_stacks();
+    buf.extend_from_slice(&usp.to_be_bytes());
+    buf.extend_from_slice(&ssp.to_be_bytes());
+
+    for word in bus.ram_snapshot().iter() {
+        buf.extend_from_slice(&word.to_be_bytes());
+    }
+
+    buf
+}
+
+/// Restores a state blob previously produced by `save`, replacing
+/// `register`'s and `bus`'s contents in place and returning the running
+/// flag for the caller to store. `kind` ("CPU" or "VM") only shapes the
+/// error messages, so loading the wrong machine's blob is diagnosable
+/// instead of just producing garbage state.
+pub(crate) fn load(
+    bytes: &[u8],
+    register: &mut Register,
+    bus: &mut Bus,
+    kind: &str,
+) -> Result<bool, String> {
+    let expected_len = 7 + 24 + (u16::MAX as usize + 1) * 2;
+    if bytes.len() != expected_len {
+        return Err(format!(
+            "wrong {} state size: expected {} bytes, got {}",
+            kind,
+            expected_len,
+            bytes.len()
+        ));
+    }
+    if &bytes[0..4] != MAGIC {
+        return Err(format!("not an LC3 {} state blob", kind));
+    }
+
+    let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(format!("unsupported {} state version `{}`", kind, version));
+    }
+
+    let mut words = bytes[7..]
+        .chunks_exact(2)
+        .map(|w| u16::from_be_bytes([w[0], w[1]]));
+
+    for r in REGISTER_ORDER {
+        let val = words
+            .next()
+            .ok_or_else(|| format!("truncated {} state", kind))?;
+        register.write(r, val);
+    }
+
+    let usp = words
+        .next()
+        .ok_or_else(|| format!("truncated {} state", kind))?;
+    let ssp = words
+        .next()
+        .ok_or_else(|| format!("truncated {} state", kind))?;
+    register.restore_banked_stacks(usp, ssp);
+
+    let mut ram = [0u16; u16::MAX as usize + 1];
+    for slot in ram.iter_mut() {
+        *slot = words
+            .next()
+            .ok_or_else(|| format!("truncated {} state", kind))?;
+    }
+    bus.load_ram(ram);
+
+    Ok(bytes[6] != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Addressable;
+
+    #[test]
+    fn save_then_load_round_trips_registers_and_ram() {
+        let mut register = Register::new();
+        register.write(R::_0, 0x1234);
+        register.write(R::PC, 0x3000);
+        let mut bus = Bus::new();
+        bus.write(0x3000, 0xdead);
+
+        let blob = save(&register, &bus, true);
+
+        let mut restored_register = Register::new();
+        let mut restored_bus = Bus::new();
+        let running = load(&blob, &mut restored_register, &mut restored_bus, "CPU").unwrap();
+
+        assert!(running);
+        assert_eq!(restored_register.read(R::_0), 0x1234);
+        assert_eq!(restored_register.read(R::PC), 0x3000);
+        assert_eq!(restored_bus.read(0x3000), 0xdead);
+    }
+
+    #[test]
+    fn load_rejects_a_truncated_blob() {
+        let err = load(&[0u8; 4], &mut Register::new(), &mut Bus::new(), "VM").unwrap_err();
+        assert!(err.contains("wrong VM state size"));
+    }
+}
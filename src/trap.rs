@@ -0,0 +1,118 @@
+//! Bodies of the standard LC-3 TRAP service routines, shared between `CPU`
+//! and `VM`. Dispatch on the trap vector happens once, in `exec`'s
+//! `mnemonic_trap`; the routines themselves only ever need register access
+//! and a way to read/write memory, so they live here apart from it.
+use crate::register::{Register, R};
+use std::io::{self, Write};
+
+// TRAP x20: read one character into R0, no echo. `read_byte` is the bus's
+// `blocking_read_byte`, not a direct `io::stdin()` read, so this can never
+// race the MMIO keyboard path over who gets the next keystroke.
+pub(crate) fn trap_getc(
+    register: &mut Register,
+    mut read_byte: impl FnMut() -> Result<u8, String>,
+) -> Result<(), String> {
+    let byte = read_byte()?;
+    register.write(R::_0, byte as u16);
+    Ok(())
+}
+
+// TRAP x21: write the low byte of R0 to stdout.
+pub(crate) fn trap_out(register: &Register) -> Result<(), String> {
+    let ch = (register.read(R::_0) & 0x00ff) as u8;
+    print!("{}", ch as char);
+    io::stdout().flush().map_err(|e| e.to_string())
+}
+
+// TRAP x22: print consecutive memory words as ASCII, starting at R0, until a
+// 0x0000 word. Returns the number of words printed (the terminating 0x0000
+// word is not counted), so callers that track cycles can bill the extra
+// memory accesses.
+pub(crate) fn trap_puts(
+    register: &Register,
+    mem_read: impl Fn(u16) -> u16,
+) -> Result<u32, String> {
+    let mut addr = register.read(R::_0);
+    let mut words_read = 0u32;
+    loop {
+        let word = mem_read(addr);
+        if word == 0 {
+            break;
+        }
+        print!("{}", (word & 0x00ff) as u8 as char);
+        addr = addr.wrapping_add(1);
+        words_read += 1;
+    }
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    Ok(words_read)
+}
+
+// TRAP x23: prompt, then read+echo one character into R0. Same `read_byte`
+// contract as `trap_getc`.
+pub(crate) fn trap_in(
+    register: &mut Register,
+    mut read_byte: impl FnMut() -> Result<u8, String>,
+) -> Result<(), String> {
+    print!("Enter a character: ");
+    io::stdout().flush().map_err(|e| e.to_string())?;
+
+    let byte = read_byte()?;
+
+    print!("{}", byte as char);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+
+    register.write(R::_0, byte as u16);
+    Ok(())
+}
+
+// TRAP x24: packed string, two characters per word (low byte first), until a
+// 0x0000 word. Returns the number of words printed, not counting the
+// terminator, same reason as `trap_puts`.
+pub(crate) fn trap_putsp(
+    register: &Register,
+    mem_read: impl Fn(u16) -> u16,
+) -> Result<u32, String> {
+    let mut addr = register.read(R::_0);
+    let mut words_read = 0u32;
+    loop {
+        let word = mem_read(addr);
+        if word == 0 {
+            break;
+        }
+
+        let lo = (word & 0x00ff) as u8;
+        print!("{}", lo as char);
+
+        let hi = (word >> 8) as u8;
+        if hi != 0 {
+            print!("{}", hi as char);
+        }
+
+        addr = addr.wrapping_add(1);
+        words_read += 1;
+    }
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    Ok(words_read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn putsp_counts_one_word_for_two_packed_characters() {
+        let register = Register::new();
+        // "Hi" packed low-byte-first, then a terminating 0x0000 word.
+        let memory = [('i' as u16) << 8 | 'H' as u16, 0x0000];
+        let words_read = trap_putsp(&register, |addr| memory[addr as usize]).unwrap();
+        assert_eq!(words_read, 1);
+    }
+
+    #[test]
+    fn putsp_stops_before_a_null_word() {
+        let register = Register::new();
+        let memory = [0x0000u16];
+        let words_read = trap_putsp(&register, |addr| memory[addr as usize]).unwrap();
+        assert_eq!(words_read, 0);
+    }
+}
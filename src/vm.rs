@@ -1,37 +1,9 @@
-use crate::register::{Flag, Mode, Register, R};
-use crate::{bit, reg_1st, reg_2nd, sign_extend};
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
-use std::convert::{TryFrom, TryInto};
-
-#[derive(Debug, FromPrimitive)]
-#[repr(u16)]
-pub enum OpCode {
-    BR,   // 0000
-    ADD,  // 0001
-    LD,   // 0010
-    ST,   // 0011
-    JSR,  // 0100
-    AND,  // 0101
-    LDR,  // 0110
-    STR,  // 0111
-    RTI,  // 1000
-    NOT,  // 1001
-    LDI,  // 1010
-    STI,  // 1011
-    JMP,  // 1100 // JMP R7 == RET
-    RES,  // 1101 // reserved
-    LEA,  // 1110
-    TRAP, // 1111
-}
-
-impl TryFrom<u16> for OpCode {
-    type Error = String;
-
-    fn try_from(val: u16) -> Result<Self, Self::Error> {
-        OpCode::from_u16(val).ok_or(format!("unknown opcode `{}`", val))
-    }
-}
+use crate::bus::{Addressable, Bus, Irq};
+use crate::exec;
+use crate::register::{Register, R};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
 
 pub struct Args {
     pub image: Option<String>,
@@ -39,245 +11,172 @@ pub struct Args {
 }
 
 pub struct VM {
-    memory: [u16; u16::MAX as usize + 1],
+    bus: Bus,
     register: Register,
     running: bool,
+    pub(crate) breakpoints: HashSet<u16>,
+    // See `Debuggable::run` in `debugger`: the PC of the breakpoint last
+    // reported, so the next `run` can step over it instead of reporting it
+    // again with zero forward progress.
+    pub(crate) last_breakpoint_hit: Option<u16>,
 }
 
 impl Default for VM {
     fn default() -> Self {
         VM {
-            memory: [0; u16::MAX as usize + 1],
+            bus: Bus::new(),
             register: Register::new(),
             running: false,
+            breakpoints: HashSet::new(),
+            last_breakpoint_hit: None,
         }
     }
 }
 
 impl VM {
-    pub fn boot(&mut self, args: Args) {
-        self.register.write(R::PC, args.offset);
-        self.running = true;
-    }
-
-    pub fn is_running(&self) -> bool {
-        self.running
-    }
-
-    pub fn next(&mut self) -> Result<(), String> {
-        let boot_addr = self.register.read_incr(R::PC);
-        let instr: u16 = self.read_memory(boot_addr);
-        let opcode: OpCode = (instr >> 12).try_into()?;
-
-        match opcode {
-            OpCode::BR => self.mnemonic_br(instr)?,
-            OpCode::ADD => self.mnemonic_add(instr)?,
-            OpCode::LD => self.mnemonic_ld(instr)?,
-            OpCode::ST => self.mnemonic_st(instr)?,
-            OpCode::JSR => self.mnemonic_jsr(instr)?,
-            OpCode::AND => self.mnemonic_and(instr)?,
-            OpCode::LDR => self.mnemonic_ldr(instr)?,
-            OpCode::STR => self.mnemonic_str(instr)?,
-            OpCode::RTI => self.mnemonic_rti(instr)?,
-            OpCode::NOT => self.mnemonic_not(instr)?,
-            OpCode::LDI => self.mnemonic_ldi(instr)?,
-            OpCode::STI => self.mnemonic_sti(instr)?,
-            OpCode::JMP => self.mnemonic_jmp(instr)?,
-            OpCode::RES => self.mnemonic_res(instr)?,
-            OpCode::LEA => self.mnemonic_lea(instr)?,
-            OpCode::TRAP => self.mnemonic_trap(instr)?,
-        }
-
-        Ok(())
-    }
+    pub fn boot(args: Args) -> Result<Self, String> {
+        let mut vm = VM::default();
 
-    pub fn abort(&mut self) {
-        self.running = false
-    }
+        let pc = match args.image {
+            Some(path) => vm.load_image(&path)?,
+            None => args.offset,
+        };
+        vm.register.write(R::PC, pc);
+        vm.running = true;
 
-    pub fn read_memory(&self, addr: u16) -> u16 {
-        self.memory[addr as usize]
+        Ok(vm)
     }
 
-    pub fn write_memory(&mut self, addr: u16, val: u16) {
-        self.memory[addr as usize] = val;
-    }
+    // Loads a standard LC-3 `.obj` image: a big-endian origin word followed by
+    // big-endian words placed sequentially into memory starting at that origin.
+    // Returns the origin so the caller can set the PC.
+    fn load_image(&mut self, path: &str) -> Result<u16, String> {
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
 
-    fn mnemonic_br(&mut self, args: u16) -> Result<(), String> {
-        let offset = sign_extend(args & 0x9, 9);
-
-        let nzp = (args >> 9) & 0x7;
-        if nzp == 0 {
-            self.register
-                .write(R::PC, self.register.read(R::PC).wrapping_add(offset));
-            return Ok(());
+        if bytes.len() < 2 || bytes.len() % 2 != 0 {
+            return Err(format!("truncated image file `{}`", path));
         }
 
-        let n: bool = bit(args, 11) == 1;
-        let z: bool = bit(args, 10) == 1;
-        let p: bool = bit(args, 9) == 1;
-
-        let flag = self.register.get_flag()?;
+        let mut words = bytes.chunks_exact(2).map(|w| u16::from_be_bytes([w[0], w[1]]));
+        let origin = words.next().unwrap();
 
-        if (n && flag == Flag::Negative)
-            || (z && flag == Flag::Zero)
-            || (p && flag == Flag::Positive)
-        {
-            self.register
-                .write(R::PC, self.register.read(R::PC).wrapping_add(offset));
+        let mut addr = origin;
+        for word in words {
+            self.write_memory(addr, word);
+            addr = addr.wrapping_add(1);
         }
 
-        Ok(())
-    }
-
-    fn mnemonic_imm5_or_sr2<F>(&mut self, args: u16, func: F) -> Result<(), String>
-    where
-        F: Fn(u16, u16) -> u16,
-    {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        let imm_flag: u16 = bit(args, 5);
-
-        if imm_flag == 1 {
-            self.register.write(
-                r0,
-                func(self.register.read(r1), sign_extend(args & 0x1f, 5)),
-            );
-        } else {
-            let r2: R = (args & 0x7).try_into()?;
-            self.register
-                .write(r0, func(self.register.read(r1), self.register.read(r2)));
-        }
-        self.register.update_flag(r0);
-        Ok(())
-    }
-
-    fn mnemonic_add(&mut self, args: u16) -> Result<(), String> {
-        self.mnemonic_imm5_or_sr2(args, |r1, r2| r1.wrapping_add(r2))
+        Ok(origin)
     }
 
-    fn mnemonic_and(&mut self, args: u16) -> Result<(), String> {
-        self.mnemonic_imm5_or_sr2(args, |r1, r2| r1 & r2)
+    pub fn is_running(&self) -> bool {
+        self.running
     }
 
-    fn mnemonic_ldi(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let pc_offset = sign_extend(args & 0x1ff, 9);
-        self.register.write(
-            r0,
-            self.read_memory(self.read_memory(self.register.read(R::PC).wrapping_add( pc_offset))),
-        );
-        self.register.update_flag(r0);
-        Ok(())
-    }
+    pub fn next(&mut self) -> Result<(), String> {
+        let current_pl = self.register.get_level()? as u16;
+        if let Some(irq) = self.bus.take_irq(current_pl) {
+            self.service_irq(irq)?;
+        }
 
-    fn mnemonic_ld(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let offset: u16 = sign_extend(args & 0x01ff, 9);
-        self.register
-            .write(r0, self.read_memory(self.register.read(R::PC).wrapping_add(offset)));
-        self.register.update_flag(r0);
-        Ok(())
-    }
+        exec::step(&mut self.register, &mut self.bus, &mut self.running)?;
 
-    fn mnemonic_st(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let offset: u16 = sign_extend(args & 0x01ff, 9);
-        self.write_memory(self.register.read(R::PC).wrapping_add(offset), self.register.read(r0));
         Ok(())
     }
 
-    fn mnemonic_res(&mut self, _: u16) -> Result<(), String> {
-        Err("reserved opcode".to_string())
-    }
-
-    fn mnemonic_jsr(&mut self, args: u16) -> Result<(), String> {
-        let mode = bit(args, 11);
-
-        self.register.write(R::_7, self.register.read(R::PC));
-
-        if mode == 1 {
-            self.register.write(
-                R::PC,
-                self.register.read(R::PC).wrapping_add(sign_extend(args & 0x07ff, 11)),
-            );
-            return Ok(());
-        }
-
-        let r0: R = reg_2nd(args)?;
-        self.register.write(R::PC, self.register.read(r0));
-        Ok(())
+    pub fn abort(&mut self) {
+        self.running = false
     }
 
-    fn mnemonic_ldr(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        let offset = sign_extend(args & 0x3f, 6);
-
-        self.register
-            .write(r0, self.read_memory(self.register.read(r1).wrapping_add(offset)));
-        self.register.update_flag(r0);
-        Ok(())
+    pub fn reg_load(&self, r: R) -> u16 {
+        self.register.read(r)
     }
 
-    fn mnemonic_str(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        let offset = sign_extend(args & 0x01ff, 9);
-
-        self.write_memory(self.register.read(r1).wrapping_add(offset), self.register.read(r0));
-        Ok(())
+    pub fn reg_store(&mut self, r: R, val: u16) {
+        self.register.write(r, val);
     }
 
-    fn mnemonic_rti(&mut self, _: u16) -> Result<(), String> {
-        if self.register.get_mode()? == Mode::Privilege {
-            let addr = self.register.read_incr(R::_6);
-            self.register.write(R::PC, self.read_memory(addr));
-
-            let addr = self.register.read_incr(R::_6);
-            self.register.write(R::PSR, self.read_memory(addr));
-            return Ok(());
-        }
-
-        self.abort();
-        Err("illegal RTI from user mode".to_string())
+    pub fn read_memory(&self, addr: u16) -> u16 {
+        self.bus.read(addr)
     }
 
-    fn mnemonic_not(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let r1: R = reg_2nd(args)?;
-        self.register.write(r0, !self.register.read(r1));
-        self.register.update_flag(r0);
-        Ok(())
+    pub fn write_memory(&mut self, addr: u16, val: u16) {
+        self.bus.write(addr, val);
     }
 
-    fn mnemonic_sti(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let offset = sign_extend(args & 0x1ff, 9);
-        self.write_memory(
-            self.read_memory(self.register.read(R::PC).wrapping_add(offset)),
-            self.register.read(r0),
-        );
-        Ok(())
+    /// Serializes the complete machine state — registers, banked stack
+    /// pointers, the running flag, and all 64K of memory — into a blob that
+    /// `load_state` can restore exactly, e.g. to dump to disk between
+    /// `next` calls and resume a long-running program later. See
+    /// `snapshot` for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        crate::snapshot::save(&self.register, &self.bus, self.running)
     }
 
-    fn mnemonic_jmp(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_2nd(args)?;
-        self.register.write(R::PC, self.register.read(r0));
+    /// Restores a state blob previously produced by `save_state`, replacing
+    /// this machine's registers, banked stack pointers, running flag, and
+    /// memory in place.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.running = crate::snapshot::load(bytes, &mut self.register, &mut self.bus, "VM")?;
         Ok(())
     }
 
-    fn mnemonic_lea(&mut self, args: u16) -> Result<(), String> {
-        let r0: R = reg_1st(args)?;
-        let offset = sign_extend(args & 0x01ff, 9);
-        self.register.write(r0, self.register.read(R::PC).wrapping_add(offset));
-        self.register.update_flag(r0);
-        Ok(())
+    // See `interrupt::service_irq`.
+    fn service_irq(&mut self, irq: Irq) -> Result<(), String> {
+        crate::interrupt::service_irq(&mut self.register, &mut self.bus, irq)
     }
+}
 
-    fn mnemonic_trap(&mut self, args: u16) -> Result<(), String> {
-        self.register.write(R::_7, self.register.read(R::PC));
-        self.register.write(R::PC, self.read_memory(args & 0x00ff));
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write as _;
+
+    #[test]
+    fn load_image_reads_big_endian_words_into_memory() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("lc3_test_image_{}.obj", std::process::id()));
+
+        // Origin 0x3000, followed by two big-endian data words.
+        let bytes: [u8; 6] = [0x30, 0x00, 0x12, 0x34, 0x56, 0x78];
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(&bytes)
+            .unwrap();
+
+        let mut vm = VM::default();
+        let origin = vm.load_image(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(origin, 0x3000);
+        assert_eq!(vm.read_memory(0x3000), 0x1234);
+        assert_eq!(vm.read_memory(0x3001), 0x5678);
+    }
+
+    #[test]
+    fn service_irq_pushes_psr_and_pc_then_vectors_through_the_table() {
+        let mut vm = VM::boot(Args {
+            offset: 0x3000,
+            image: None,
+        })
+        .unwrap();
+        vm.register.write(R::_6, 0x4000);
+        vm.write_memory(0x0100 + 0x80, 0x1234);
+
+        vm.service_irq(Irq {
+            priority: 4,
+            vector: 0x80,
+        })
+        .unwrap();
+
+        assert_eq!(vm.reg_load(R::PC), 0x1234);
+        assert_eq!(vm.reg_load(R::_6), 0x3ffe);
+        assert_eq!(vm.read_memory(0x3ffe), 0x3000);
+        assert_eq!(
+            vm.register.get_level().unwrap() as u16,
+            crate::register::PL::_4 as u16
+        );
     }
 }